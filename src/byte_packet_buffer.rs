@@ -1,34 +1,130 @@
-use std::{error::Error, fmt::Display};
+use std::{collections::HashMap, error::Error, fmt::Display, io::Read};
 
 #[derive(Debug)]
 pub enum BytePacketBufferError {
     EndOfBuffer,
     JumpLimitExceeded,
+    InvalidJump,
+    LabelTooLong,
+    NameTooLong,
+    PointerRangeExceeded,
+    /// The attached `source` closed before `needed` bytes arrived. Unlike
+    /// `EndOfBuffer`, this is terminal: no amount of retrying will ever
+    /// produce more data, so callers must not treat it as "buffer more
+    /// and try again".
+    Eof,
+    /// Propagates an I/O error encountered while filling from `source`.
+    Io(std::io::Error),
 }
 
 impl Display for BytePacketBufferError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self.source())
+        write!(f, "{:?}", self)
     }
 }
 
 impl Error for BytePacketBufferError {}
 
-pub struct BytePacketBuffer {
-    pub buf: [u8; 512],
+/// Default capacity for a freshly allocated buffer. Large enough for the
+/// vast majority of UDP responses while still growing on demand for TCP
+/// and EDNS(0) messages that exceed the legacy 512-byte limit.
+const DEFAULT_CAPACITY: usize = 512;
+
+/// Most bytes to pull from a streaming source per `read` that `fill`
+/// makes; `fill` keeps reading until it has as many bytes as it needs.
+const FILL_CHUNK_SIZE: usize = 4096;
+
+pub struct BytePacketBuffer<'a> {
+    pub buf: Vec<u8>,
     pub pos: usize,
+    /// When present, `buf` is filled on demand by reading from this
+    /// source rather than being fully materialized up front.
+    source: Option<&'a mut dyn Read>,
+    /// Maps each fully-qualified name (or suffix of one) already written
+    /// by `write_qname` to the absolute offset it starts at, so later
+    /// names can point back into it instead of repeating the labels.
+    name_offsets: HashMap<String, usize>,
 }
 
-impl BytePacketBuffer {
+impl<'a> BytePacketBuffer<'a> {
     /// This gives us a fresh buffer for holding the packet contents, and a
     /// field for keeping track of where we are.
-    pub fn new() -> BytePacketBuffer {
+    pub fn new() -> BytePacketBuffer<'static> {
+        BytePacketBuffer::with_capacity(DEFAULT_CAPACITY)
+    }
+
+    /// Allocate a buffer with room for `capacity` bytes up front. Useful
+    /// when the caller knows a message will exceed the legacy 512-byte
+    /// UDP limit (e.g. TCP or EDNS(0)) and wants to avoid reallocating
+    /// while parsing.
+    pub fn with_capacity(capacity: usize) -> BytePacketBuffer<'static> {
+        BytePacketBuffer {
+            buf: vec![0; capacity],
+            pos: 0,
+            source: None,
+            name_offsets: HashMap::new(),
+        }
+    }
+
+    /// Build a buffer from an already-received message.
+    pub fn from_bytes(bytes: &[u8]) -> BytePacketBuffer<'static> {
+        BytePacketBuffer {
+            buf: bytes.to_vec(),
+            pos: 0,
+            source: None,
+            name_offsets: HashMap::new(),
+        }
+    }
+
+    /// Wrap a stream (e.g. a TCP socket) so the buffer is filled on
+    /// demand as parsing consumes bytes, instead of requiring the whole
+    /// message to be read up front.
+    pub fn from_reader(source: &'a mut dyn Read) -> BytePacketBuffer<'a> {
         BytePacketBuffer {
-            buf: [0; 512],
+            buf: Vec::new(),
             pos: 0,
+            source: Some(source),
+            name_offsets: HashMap::new(),
         }
     }
 
+    /// Make sure at least `needed` bytes are buffered, pulling more from
+    /// `source` in `FILL_CHUNK_SIZE` increments if necessary. A buffer
+    /// with no source (already fully materialized) simply reports
+    /// `EndOfBuffer` once `needed` runs past what's there.
+    fn fill(&mut self, needed: usize) -> Result<(), BytePacketBufferError> {
+        if self.buf.len() >= needed {
+            return Ok(());
+        }
+
+        let source = self
+            .source
+            .as_mut()
+            .ok_or(BytePacketBufferError::EndOfBuffer)?;
+
+        let mut chunk = vec![0u8; FILL_CHUNK_SIZE];
+        while self.buf.len() < needed {
+            let read = source.read(&mut chunk).map_err(BytePacketBufferError::Io)?;
+            if read == 0 {
+                return Err(BytePacketBufferError::Eof);
+            }
+            self.buf.extend_from_slice(&chunk[..read]);
+        }
+        Ok(())
+    }
+
+    /// Read a DNS-over-TCP message: a 2-byte big-endian length prefix
+    /// followed by exactly that many message bytes (RFC 1035 §4.2.2).
+    /// Drops the prefix and everything buffered before it, so offset 0
+    /// is the message's first byte (compression pointers are relative to
+    /// it), and ensures the whole message is buffered before parsing begins.
+    pub fn read_tcp_message(&mut self) -> Result<(), BytePacketBufferError> {
+        let len = self.read_u16()? as usize;
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.fill(len)
+    }
+
     /// Current position within buffer
     fn pos(&self) -> usize {
         self.pos
@@ -45,10 +141,8 @@ impl BytePacketBuffer {
     }
 
     /// Read a single byte and move the position one step forward
-    fn read(&mut self) -> Result<u8, BytePacketBufferError> {
-        if self.pos >= 512 {
-            return Err(BytePacketBufferError::EndOfBuffer);
-        }
+    pub fn read(&mut self) -> Result<u8, BytePacketBufferError> {
+        self.fill(self.pos + 1)?;
         let res = self.buf[self.pos];
         self.pos += 1;
         Ok(res)
@@ -56,28 +150,24 @@ impl BytePacketBuffer {
 
     /// Get a single byte, without changing the buffer position
     fn get(&mut self, pos: usize) -> Result<u8, BytePacketBufferError> {
-        if pos >= 512 {
-            return Err(BytePacketBufferError::EndOfBuffer);
-        }
+        self.fill(pos + 1)?;
         Ok(self.buf[pos])
     }
 
     /// Get a range of bytes
     fn get_range(&mut self, start: usize, len: usize) -> Result<&[u8], BytePacketBufferError> {
-        if start + len >= 512 {
-            return Err(BytePacketBufferError::EndOfBuffer);
-        }
+        self.fill(start + len)?;
         Ok(&self.buf[start..start + len as usize])
     }
 
     /// Read two bytes, stepping two steps forward
-    fn read_u16(&mut self) -> Result<u16, BytePacketBufferError> {
+    pub fn read_u16(&mut self) -> Result<u16, BytePacketBufferError> {
         let res = ((self.read()? as u16) << 8) | (self.read()? as u16);
         Ok(res)
     }
 
     /// Read four bytes, stepping four steps forward
-    fn read_u32(&mut self) -> Result<u32, BytePacketBufferError> {
+    pub fn read_u32(&mut self) -> Result<u32, BytePacketBufferError> {
         let res = ((self.read()? as u32) << 24)
             | ((self.read()? as u32) << 16)
             | ((self.read()? as u32) << 8)
@@ -85,11 +175,47 @@ impl BytePacketBuffer {
         Ok(res)
     }
 
+    /// Read a qname, rewinding the cursor instead of erroring when the
+    /// name straddles a buffer boundary.
+    ///
+    /// Returns `Ok(true)` once the whole name has been decoded into
+    /// `outstr` and the cursor committed past it. Returns `Ok(false)` if
+    /// decoding ran into `EndOfBuffer` partway through a label or jump;
+    /// in that case `self.pos` is restored to where it was on entry and
+    /// `outstr` is left unchanged, so the caller can buffer more data
+    /// (e.g. from a stream) and retry.
+    fn try_read_qname(&mut self, outstr: &mut String) -> Result<bool, BytePacketBufferError> {
+        let start_pos = self.pos();
+        let mut scratch = String::new();
+        match self.read_qname_inner(&mut scratch) {
+            Ok(()) => {
+                outstr.push_str(&scratch);
+                Ok(true)
+            }
+            Err(BytePacketBufferError::EndOfBuffer) => {
+                self.seek(start_pos);
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read a qname, erroring on a partial name instead of signalling
+    /// "need more bytes". Thin wrapper around [`try_read_qname`] for
+    /// callers that already have the whole packet buffered.
+    pub fn read_qname(&mut self, outstr: &mut String) -> Result<(), BytePacketBufferError> {
+        if self.try_read_qname(outstr)? {
+            Ok(())
+        } else {
+            Err(BytePacketBufferError::EndOfBuffer)
+        }
+    }
+
     /// Read a qname
     ///
     /// It is difficult to read domain names while taking labels into consideration.
     /// Can achieve via [3]www[6]google[3]com[0] and app www.google.com to outstr.
-    fn read_qname(&mut self, outstr: &mut String) -> Result<(), BytePacketBufferError> {
+    fn read_qname_inner(&mut self, outstr: &mut String) -> Result<(), BytePacketBufferError> {
         // Track position locally as jumps can occurr.
         // This allows us to move past the current qname while keeping a position
         // in the current qname.
@@ -119,7 +245,14 @@ impl BytePacketBuffer {
 
                 // read another byte, calc offset and jump
                 let b2 = self.get(pos + 1)? as u16;
-                let offset = (((len as u16) * 0xC0) << 8) | b2;
+                let offset = (((len as u16) & 0x3F) << 8) | b2;
+
+                // a pointer must only ever point backwards in the packet;
+                // forward and self-referential pointers are either
+                // malformed or a deliberate attempt to loop the parser
+                if offset as usize >= pos {
+                    return Err(BytePacketBufferError::InvalidJump);
+                }
                 pos = offset as usize;
 
                 jumped = true;
@@ -147,4 +280,254 @@ impl BytePacketBuffer {
         }
         Ok(())
     }
+
+    /// Write a single byte and move the position one step forward,
+    /// growing the buffer if we're writing past its current end.
+    fn write(&mut self, val: u8) -> Result<(), BytePacketBufferError> {
+        if self.pos >= self.buf.len() {
+            self.buf.push(val);
+        } else {
+            self.buf[self.pos] = val;
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Write a single byte, stepping one step forward
+    pub fn write_u8(&mut self, val: u8) -> Result<(), BytePacketBufferError> {
+        self.write(val)
+    }
+
+    /// Write two bytes, stepping two steps forward
+    pub fn write_u16(&mut self, val: u16) -> Result<(), BytePacketBufferError> {
+        self.write((val >> 8) as u8)?;
+        self.write((val & 0xFF) as u8)
+    }
+
+    /// Write four bytes, stepping four steps forward
+    pub fn write_u32(&mut self, val: u32) -> Result<(), BytePacketBufferError> {
+        self.write(((val >> 24) & 0xFF) as u8)?;
+        self.write(((val >> 16) & 0xFF) as u8)?;
+        self.write(((val >> 8) & 0xFF) as u8)?;
+        self.write((val & 0xFF) as u8)
+    }
+
+    /// Back-patch two bytes already written at `pos`, without moving the
+    /// current write cursor. Used to fill in header counts once the
+    /// records that determine them have been written.
+    pub fn set_u16(&mut self, pos: usize, val: u16) -> Result<(), BytePacketBufferError> {
+        if pos + 1 >= self.buf.len() {
+            return Err(BytePacketBufferError::EndOfBuffer);
+        }
+        self.buf[pos] = (val >> 8) as u8;
+        self.buf[pos + 1] = (val & 0xFF) as u8;
+        Ok(())
+    }
+
+    /// Write a qname, compressing it against every name written earlier
+    /// into this buffer.
+    ///
+    /// Labels are split on `.` and written as `[len]label` pairs
+    /// terminated by a zero-length label, same as `read_qname` expects.
+    /// Before writing, each suffix of `name` (the whole name, then every
+    /// shorter suffix obtained by dropping leading labels) is looked up
+    /// in `name_offsets`; the first hit is replaced with a `0xC0`-flagged
+    /// pointer to where that suffix was first written, and the remaining
+    /// labels are skipped entirely. Every suffix written out in full is
+    /// recorded the same way, so later names can point back into this one.
+    ///
+    /// Fully validated before anything is written: a name rejected with
+    /// `LabelTooLong`, `NameTooLong`, or `PointerRangeExceeded` leaves
+    /// both the buffer and `name_offsets` exactly as they were, so a
+    /// failed call can never poison a later, unrelated `write_qname`
+    /// with a pointer into an unterminated label sequence.
+    pub fn write_qname(&mut self, name: &str) -> Result<(), BytePacketBufferError> {
+        let name = name.to_lowercase();
+        if name.len() > 255 {
+            return Err(BytePacketBufferError::NameTooLong);
+        }
+
+        let labels: Vec<&str> = if name.is_empty() {
+            Vec::new()
+        } else {
+            name.split('.').collect()
+        };
+
+        for label in &labels {
+            if label.len() > 63 {
+                return Err(BytePacketBufferError::LabelTooLong);
+            }
+        }
+
+        // Find the longest already-written suffix, if any, without
+        // mutating anything yet.
+        let mut pointer = None;
+        let mut labels_to_write = labels.len();
+        for i in 0..labels.len() {
+            let suffix = labels[i..].join(".");
+            if let Some(&offset) = self.name_offsets.get(&suffix) {
+                if offset > 0x3FFF {
+                    return Err(BytePacketBufferError::PointerRangeExceeded);
+                }
+                pointer = Some(offset);
+                labels_to_write = i;
+                break;
+            }
+        }
+
+        // Validation is done; every write below is infallible, so no
+        // partial state can be left behind from here on.
+        for i in 0..labels_to_write {
+            let suffix = labels[i..].join(".");
+            self.name_offsets.insert(suffix, self.pos());
+
+            let label = labels[i];
+            self.write_u8(label.len() as u8)?;
+            for b in label.as_bytes() {
+                self.write_u8(*b)?;
+            }
+        }
+
+        match pointer {
+            Some(offset) => self.write_u16(0xC000 | offset as u16),
+            None => self.write_u8(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_read_qname_rewinds_on_incomplete_name() {
+        let mut writer = BytePacketBuffer::new();
+        writer.write_qname("www.example.com").unwrap();
+        let full = writer.buf[..writer.pos].to_vec();
+
+        // Truncate partway through the last label so the name is incomplete.
+        let mut partial = BytePacketBuffer::from_bytes(&full[..full.len() - 3]);
+        let mut outstr = String::new();
+        let complete = partial.try_read_qname(&mut outstr).unwrap();
+
+        assert!(!complete);
+        assert_eq!(outstr, "");
+        assert_eq!(partial.pos, 0);
+
+        // Once the rest of the name has arrived, the same call succeeds.
+        let mut whole = BytePacketBuffer::from_bytes(&full);
+        let mut outstr2 = String::new();
+        assert!(whole.try_read_qname(&mut outstr2).unwrap());
+        assert_eq!(outstr2, "www.example.com");
+    }
+
+    #[test]
+    fn read_qname_errors_on_incomplete_name() {
+        let mut writer = BytePacketBuffer::new();
+        writer.write_qname("incomplete.example").unwrap();
+        let full = writer.buf[..writer.pos].to_vec();
+
+        let mut partial = BytePacketBuffer::from_bytes(&full[..full.len() - 2]);
+        let mut outstr = String::new();
+        let err = partial.read_qname(&mut outstr).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::EndOfBuffer));
+    }
+
+    #[test]
+    fn read_qname_rejects_self_referential_pointer() {
+        // A pointer at offset 0 that targets itself.
+        let mut buffer = BytePacketBuffer::from_bytes(&[0xC0, 0x00]);
+        let mut outstr = String::new();
+        let err = buffer.read_qname(&mut outstr).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::InvalidJump));
+    }
+
+    #[test]
+    fn read_qname_rejects_forward_pointer() {
+        // A pointer at offset 0 that targets offset 5, ahead of itself.
+        let mut buffer = BytePacketBuffer::from_bytes(&[0xC0, 0x05, 0, 0, 0, 0]);
+        let mut outstr = String::new();
+        let err = buffer.read_qname(&mut outstr).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::InvalidJump));
+    }
+
+    #[test]
+    fn read_qname_accepts_legitimate_backward_pointer() {
+        // offset 0: the label "com" terminated by a zero-length label.
+        // offset 5: a pointer jumping back to offset 0.
+        let mut buffer = BytePacketBuffer::from_bytes(&[3, b'c', b'o', b'm', 0, 0xC0, 0x00]);
+        buffer.seek(5);
+        let mut outstr = String::new();
+        buffer.read_qname(&mut outstr).unwrap();
+        assert_eq!(outstr, "com");
+    }
+
+    #[test]
+    fn write_qname_compresses_against_previously_written_suffix() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer.write_qname("www.google.com").unwrap();
+        let first_end = buffer.pos;
+        buffer.write_qname("mail.google.com").unwrap();
+        let second_len = buffer.pos - first_end;
+
+        // "mail" written in full plus a pointer is far shorter than
+        // "mail.google.com" written out with no compression at all (17 bytes).
+        assert!(second_len < 17);
+
+        let mut reader = BytePacketBuffer::from_bytes(&buffer.buf[..buffer.pos]);
+        let mut first = String::new();
+        reader.read_qname(&mut first).unwrap();
+        assert_eq!(first, "www.google.com");
+
+        let mut second = String::new();
+        reader.read_qname(&mut second).unwrap();
+        assert_eq!(second, "mail.google.com");
+    }
+
+    #[test]
+    fn write_qname_rejects_label_over_63_bytes() {
+        let mut buffer = BytePacketBuffer::new();
+        let label = "a".repeat(64);
+        let err = buffer.write_qname(&label).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::LabelTooLong));
+    }
+
+    #[test]
+    fn write_qname_rejects_name_over_255_bytes() {
+        let mut buffer = BytePacketBuffer::new();
+        let name = format!("{}.com", "a".repeat(252));
+        let err = buffer.write_qname(&name).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::NameTooLong));
+    }
+
+    #[test]
+    fn write_qname_rejects_pointer_beyond_14_bit_range() {
+        let mut buffer = BytePacketBuffer::new();
+        buffer
+            .name_offsets
+            .insert("toofar.example".to_string(), 0x4000);
+        let err = buffer.write_qname("toofar.example").unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::PointerRangeExceeded));
+    }
+
+    #[test]
+    fn write_qname_leaves_no_trace_when_a_later_label_is_too_long() {
+        let name = format!("a.{}.com", "b".repeat(64));
+        let mut buffer = BytePacketBuffer::new();
+
+        let err = buffer.write_qname(&name).unwrap_err();
+        assert!(matches!(err, BytePacketBufferError::LabelTooLong));
+
+        // Nothing should have been written or recorded for compression.
+        assert_eq!(buffer.pos, 0);
+        assert!(buffer.name_offsets.is_empty());
+
+        // A later, unrelated name must not pick up a dangling suffix
+        // entry and point into the rejected name's unterminated bytes.
+        buffer.write_qname("a.com").unwrap();
+        let mut reader = BytePacketBuffer::from_bytes(&buffer.buf[..buffer.pos]);
+        let mut outstr = String::new();
+        reader.read_qname(&mut outstr).unwrap();
+        assert_eq!(outstr, "a.com");
+    }
 }
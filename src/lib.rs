@@ -0,0 +1,2 @@
+pub mod byte_packet_buffer;
+pub mod transport;
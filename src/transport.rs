@@ -0,0 +1,254 @@
+//! Binary-over-DNS transport: chunk an arbitrary payload into TXT-record
+//! character-strings and reassemble it again. Opt-in subsystem built on
+//! top of [`BytePacketBuffer`]'s qname/byte read-write primitives; it has
+//! no bearing on ordinary name resolution.
+
+use crate::byte_packet_buffer::{BytePacketBuffer, BytePacketBufferError};
+use std::{error::Error, fmt::Display};
+
+/// Maximum length of a single DNS `<character-string>` (RFC 1035 §3.3): a
+/// one-byte length prefix followed by up to 255 bytes of data.
+const MAX_CHARACTER_STRING_LEN: usize = 255;
+
+/// Bytes consumed by our chunk header (sequence index + total count)
+/// inside each character-string, leaving the rest for payload.
+const CHUNK_HEADER_LEN: usize = 4;
+const MAX_CHUNK_PAYLOAD_LEN: usize = MAX_CHARACTER_STRING_LEN - CHUNK_HEADER_LEN;
+
+/// Owner name used for every chunk record; the chunk header carries the
+/// actual ordering information, so the name itself doesn't need to.
+const CHUNK_OWNER_NAME: &str = "chunk.data";
+
+#[derive(Debug)]
+pub enum TransportError {
+    BytePacketBuffer(BytePacketBufferError),
+    MissingChunk(u16),
+    DuplicateChunk(u16),
+    InconsistentTotal,
+    /// `data` plus the chunk header would overflow the 255-byte
+    /// `<character-string>` limit.
+    ChunkTooLarge(usize),
+}
+
+impl Display for TransportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl Error for TransportError {}
+
+impl From<BytePacketBufferError> for TransportError {
+    fn from(err: BytePacketBufferError) -> Self {
+        TransportError::BytePacketBuffer(err)
+    }
+}
+
+/// A single chunk of a payload split across DNS TXT records, as produced
+/// by [`encode_payload`]. The owner name is always `CHUNK_OWNER_NAME`;
+/// the sequence index and chunk count live in a small header ahead of
+/// the TXT character-string data so [`decode_payload`] can reassemble
+/// them out of order and detect gaps or duplicates.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub name: String,
+    pub seq: u16,
+    pub total: u16,
+    pub data: Vec<u8>,
+}
+
+impl Record {
+    /// Write this chunk out as a DNS answer record: a qname (compressed
+    /// against anything already written into `buffer`), TYPE TXT, CLASS
+    /// IN, a TTL of 0 and RDLENGTH, followed by a single TXT
+    /// character-string whose first four bytes are the big-endian
+    /// `seq`/`total` header.
+    pub fn write(&self, buffer: &mut BytePacketBuffer) -> Result<(), TransportError> {
+        let character_string_len = CHUNK_HEADER_LEN + self.data.len();
+        if character_string_len > MAX_CHARACTER_STRING_LEN {
+            return Err(TransportError::ChunkTooLarge(self.data.len()));
+        }
+
+        buffer.write_qname(&self.name)?;
+        buffer.write_u16(16)?; // TYPE: TXT
+        buffer.write_u16(1)?; // CLASS: IN
+        buffer.write_u32(0)?; // TTL
+        buffer.write_u16(character_string_len as u16 + 1)?; // RDLENGTH
+        buffer.write_u8(character_string_len as u8)?;
+        buffer.write_u16(self.seq)?;
+        buffer.write_u16(self.total)?;
+        for byte in &self.data {
+            buffer.write_u8(*byte)?;
+        }
+        Ok(())
+    }
+
+    /// Read one chunk back out of `buffer` at its current position, the
+    /// inverse of [`Record::write`].
+    pub fn read(buffer: &mut BytePacketBuffer) -> Result<Record, TransportError> {
+        let mut name = String::new();
+        buffer.read_qname(&mut name)?;
+
+        // TYPE, CLASS, TTL and RDLENGTH; the character-string below
+        // carries its own length.
+        buffer.read_u16()?;
+        buffer.read_u16()?;
+        buffer.read_u32()?;
+        buffer.read_u16()?;
+
+        let character_string_len = buffer.read()? as usize;
+        let seq = buffer.read_u16()?;
+        let total = buffer.read_u16()?;
+
+        let payload_len = character_string_len
+            .checked_sub(CHUNK_HEADER_LEN)
+            .ok_or(BytePacketBufferError::EndOfBuffer)?;
+        let mut data = Vec::with_capacity(payload_len);
+        for _ in 0..payload_len {
+            data.push(buffer.read()?);
+        }
+
+        Ok(Record {
+            name,
+            seq,
+            total,
+            data,
+        })
+    }
+}
+
+/// Split an arbitrary byte blob into TXT-record character-strings small
+/// enough to fit the 255-byte limit once our chunk header is added, and
+/// wrap each one in a [`Record`] ready to be written into answer records
+/// with [`Record::write`].
+pub fn encode_payload(data: &[u8]) -> Vec<Record> {
+    if data.is_empty() {
+        return vec![Record {
+            name: CHUNK_OWNER_NAME.to_string(),
+            seq: 0,
+            total: 1,
+            data: Vec::new(),
+        }];
+    }
+
+    let chunks: Vec<&[u8]> = data.chunks(MAX_CHUNK_PAYLOAD_LEN).collect();
+    let total = chunks.len() as u16;
+
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(seq, chunk)| Record {
+            name: CHUNK_OWNER_NAME.to_string(),
+            seq: seq as u16,
+            total,
+            data: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Reassemble a payload previously split by [`encode_payload`], in
+/// sequence order regardless of the order `records` arrives in. Errors
+/// if any index in `0..total` is missing or duplicated.
+pub fn decode_payload(records: &[Record]) -> Result<Vec<u8>, TransportError> {
+    if records.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let total = records[0].total;
+    let mut chunks: Vec<Option<&[u8]>> = vec![None; total as usize];
+
+    for record in records {
+        if record.total != total {
+            return Err(TransportError::InconsistentTotal);
+        }
+        let slot = chunks
+            .get_mut(record.seq as usize)
+            .ok_or(TransportError::InconsistentTotal)?;
+        if slot.is_some() {
+            return Err(TransportError::DuplicateChunk(record.seq));
+        }
+        *slot = Some(&record.data);
+    }
+
+    let mut payload = Vec::new();
+    for (seq, chunk) in chunks.into_iter().enumerate() {
+        match chunk {
+            Some(data) => payload.extend_from_slice(data),
+            None => return Err(TransportError::MissingChunk(seq as u16)),
+        }
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_small_payload() {
+        let payload = b"hello, dns".to_vec();
+        let records = encode_payload(&payload);
+        assert_eq!(records.len(), 1);
+        assert_eq!(decode_payload(&records).unwrap(), payload);
+    }
+
+    #[test]
+    fn round_trips_multi_kilobyte_payload_through_the_wire_format() {
+        let payload: Vec<u8> = (0..8000).map(|i| (i % 251) as u8).collect();
+        let records = encode_payload(&payload);
+        assert!(records.len() > 1);
+
+        let mut write_buffer = BytePacketBuffer::new();
+        for record in &records {
+            record.write(&mut write_buffer).unwrap();
+        }
+
+        let mut read_buffer = BytePacketBuffer::from_bytes(&write_buffer.buf);
+        let decoded_records: Vec<Record> = (0..records.len())
+            .map(|_| Record::read(&mut read_buffer).unwrap())
+            .collect();
+
+        assert_eq!(decode_payload(&decoded_records).unwrap(), payload);
+    }
+
+    #[test]
+    fn rejects_payload_missing_a_chunk() {
+        let payload: Vec<u8> = vec![1; 1000];
+        let mut records = encode_payload(&payload);
+        records.remove(1);
+
+        match decode_payload(&records) {
+            Err(TransportError::MissingChunk(_)) => {}
+            other => panic!("expected MissingChunk, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_duplicated_chunk_index() {
+        let payload: Vec<u8> = vec![2; 1000];
+        let mut records = encode_payload(&payload);
+        let duplicate = records[0].clone();
+        records.push(duplicate);
+
+        match decode_payload(&records) {
+            Err(TransportError::DuplicateChunk(_)) => {}
+            other => panic!("expected DuplicateChunk, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_rejects_a_hand_built_record_over_the_character_string_limit() {
+        let oversized = Record {
+            name: CHUNK_OWNER_NAME.to_string(),
+            seq: 0,
+            total: 1,
+            data: vec![0u8; 300],
+        };
+
+        let mut buffer = BytePacketBuffer::new();
+        match oversized.write(&mut buffer) {
+            Err(TransportError::ChunkTooLarge(300)) => {}
+            other => panic!("expected ChunkTooLarge(300), got {:?}", other),
+        }
+    }
+}